@@ -0,0 +1,83 @@
+use std::f32::consts::PI;
+
+use anyhow::Context;
+use chip8::Buzzer;
+use rodio::{OutputStream, Sink, Source};
+
+const FREQUENCY_HZ: f32 = 440.0;
+const SAMPLE_RATE_HZ: u32 = 44100;
+const AMPLITUDE: f32 = 0.2;
+
+/// An endless square wave at `FREQUENCY_HZ`, generated on the fly rather than
+/// loaded from a file since all we need is a simple beep.
+struct SquareWave {
+    sample_index: u64,
+}
+
+impl SquareWave {
+    fn new() -> Self {
+        Self { sample_index: 0 }
+    }
+}
+
+impl Iterator for SquareWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let phase = self.sample_index as f32 * FREQUENCY_HZ * 2.0 * PI / SAMPLE_RATE_HZ as f32;
+        self.sample_index += 1;
+        Some(if phase.sin() >= 0.0 { AMPLITUDE } else { -AMPLITUDE })
+    }
+}
+
+impl Source for SquareWave {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE_HZ
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+/// Plays the CHIP-8 beep through the default audio device. The square wave is
+/// appended once and left paused; `set_playing` just toggles play/pause so
+/// there's no per-call allocation on the hot path.
+pub(crate) struct RodioBuzzer {
+    // Held only to keep the output stream alive; never read.
+    _stream: OutputStream,
+    sink: Sink,
+}
+
+impl RodioBuzzer {
+    pub(crate) fn new() -> anyhow::Result<Self> {
+        let (stream, handle) =
+            OutputStream::try_default().context("Couldn't open audio output")?;
+        let sink = Sink::try_new(&handle).context("Couldn't create audio sink")?;
+        sink.append(SquareWave::new());
+        sink.pause();
+
+        Ok(Self {
+            _stream: stream,
+            sink,
+        })
+    }
+}
+
+impl Buzzer for RodioBuzzer {
+    fn set_playing(&mut self, on: bool) {
+        if on {
+            self.sink.play();
+        } else {
+            self.sink.pause();
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{self, Write};
+
+use chip8::State;
+
+/// Interactive command loop that sits at the top of the fetch-decode-execute
+/// loop in `main`. It can pause execution at a breakpoint, single-step one
+/// instruction at a time, trace every instruction as it runs, and inspect or
+/// mutate a paused `State` from stdin.
+pub(crate) struct Debugger {
+    breakpoints: Vec<u16>,
+    pub(crate) trace_only: bool,
+    last_command: Option<String>,
+    repeat: u32,
+    stepping: bool,
+}
+
+impl Debugger {
+    pub(crate) fn new(start_paused: bool) -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            trace_only: false,
+            last_command: None,
+            repeat: 0,
+            stepping: start_paused,
+        }
+    }
+
+    /// Called once per instruction, before it is fetched. Returns `true` if
+    /// the instruction loop should pause and hand control to `repl`.
+    pub(crate) fn should_stop(&mut self, program_counter: u16) -> bool {
+        if self.breakpoints.contains(&program_counter) {
+            self.stepping = true;
+            return true;
+        }
+
+        if self.stepping {
+            if self.repeat == 0 {
+                return true;
+            }
+            self.repeat -= 1;
+        }
+
+        false
+    }
+
+    /// Prints the instruction about to execute and the registers it may
+    /// touch. Used by "trace" mode; peeks `memory` rather than taking the
+    /// already-fetched instruction, since `State::step` owns fetching.
+    pub(crate) fn trace(&self, state: &State) {
+        let pc = state.program_counter as usize;
+        let instruction = state
+            .memory
+            .get(pc..pc + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+
+        match instruction {
+            Some(instruction) => println!(
+                "{:04X}: {:04X}  {}  V={:02X?} I={:04X}",
+                pc,
+                instruction,
+                chip8::disassemble(instruction),
+                state.variable_registers,
+                state.index_register
+            ),
+            None => println!("{:04X}: <out of bounds>", pc),
+        }
+    }
+
+    /// Drives the command loop while the emulator is paused, printing the
+    /// prompt and reading commands from stdin. Returns once the user asks to
+    /// continue or single-step, at which point the caller resumes the
+    /// fetch-decode-execute loop.
+    pub(crate) fn repl(&mut self, state: &mut State) -> anyhow::Result<()> {
+        loop {
+            print!("chip8dbg [{:04X}]> ", state.program_counter);
+            io::stdout().flush()?;
+
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            let line = line.trim();
+
+            let command = if line.is_empty() {
+                self.last_command.clone().unwrap_or_default()
+            } else {
+                line.to_string()
+            };
+            self.last_command = Some(command.clone());
+
+            let mut parts = command.split_whitespace();
+            match parts.next() {
+                Some("b") | Some("break") => match parts.next().and_then(parse_hex_u16) {
+                    Some(addr) => {
+                        self.breakpoints.push(addr);
+                        println!("Breakpoint set at {:04X}", addr);
+                    }
+                    None => println!("Usage: break <hex addr>"),
+                },
+                Some("t") | Some("trace") => {
+                    self.trace_only = !self.trace_only;
+                    println!("Trace mode {}", if self.trace_only { "on" } else { "off" });
+                }
+                Some("s") | Some("step") => {
+                    self.stepping = true;
+                    self.repeat = parts
+                        .next()
+                        .and_then(|n| n.parse::<u32>().ok())
+                        .unwrap_or(1)
+                        .saturating_sub(1);
+                    return Ok(());
+                }
+                Some("c") | Some("continue") => {
+                    self.stepping = false;
+                    self.trace_only = false;
+                    return Ok(());
+                }
+                Some("r") | Some("regs") => println!("{:?}", state),
+                Some("m") | Some("mem") => match parts.next() {
+                    Some("write") => match parts.next().and_then(parse_hex_usize) {
+                        Some(addr) => {
+                            let bytes: Option<Vec<u8>> =
+                                parts.map(|b| u8::from_str_radix(b, 16).ok()).collect();
+                            match bytes {
+                                Some(bytes) => {
+                                    let end = addr.checked_add(bytes.len());
+                                    match end.and_then(|end| state.memory.get_mut(addr..end)) {
+                                        Some(dst) => {
+                                            dst.copy_from_slice(&bytes);
+                                            println!(
+                                                "Wrote {} byte(s) at {:04X}",
+                                                bytes.len(),
+                                                addr
+                                            );
+                                        }
+                                        None => println!("Address out of bounds: {:04X}", addr),
+                                    }
+                                }
+                                None => println!("Usage: mem write <hex addr> <hex byte>..."),
+                            }
+                        }
+                        None => println!("Usage: mem write <hex addr> <hex byte>..."),
+                    },
+                    Some(addr) => match parse_hex_usize(addr) {
+                        Some(addr) => {
+                            let len = parts.next().and_then(|n| n.parse().ok()).unwrap_or(16);
+                            let end = addr.checked_add(len).map(|end| end.min(state.memory.len()));
+                            match end.and_then(|end| state.memory.get(addr..end)) {
+                                Some(bytes) => println!("{:04X}: {:02X?}", addr, bytes),
+                                None => println!("Address out of bounds: {:04X}", addr),
+                            }
+                        }
+                        None => println!("Usage: mem <hex addr> [len] | mem write <hex addr> <hex byte>..."),
+                    },
+                    None => println!("Usage: mem <hex addr> [len] | mem write <hex addr> <hex byte>..."),
+                },
+                Some("q") | Some("quit") => std::process::exit(0),
+                _ => println!(
+                    "Unknown command {:?} (break|trace|step|continue|regs|mem|quit)",
+                    command
+                ),
+            }
+        }
+    }
+}
+
+fn parse_hex_u16(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_hex_usize(s: &str) -> Option<usize> {
+    usize::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
@@ -0,0 +1,55 @@
+/// Renders a single CHIP-8/SUPER-CHIP instruction as a mnemonic, mirroring
+/// the nibble match in `State::step`. Shared by the debugger's trace mode
+/// and by `State::crash_report`, so both show instructions the same way.
+pub fn disassemble(instruction: u16) -> String {
+    let [[n1, n2], [n3, n4]] = instruction.to_be_bytes().map(|b| [b >> 4, b & 0x0F]);
+    let nnn = instruction & 0x0FFF;
+    let nn = (instruction & 0x00FF) as u8;
+
+    match [n1, n2, n3, n4] {
+        [0x0, 0x0, 0xE, 0x0] => "CLS".to_string(),
+        [0x0, 0x0, 0xE, 0xE] => "RET".to_string(),
+        [0x0, 0x0, 0xC, n] => format!("SCD {n:X}"),
+        [0x0, 0x0, 0xF, 0xB] => "SCR".to_string(),
+        [0x0, 0x0, 0xF, 0xC] => "SCL".to_string(),
+        [0x0, 0x0, 0xF, 0xD] => "EXIT".to_string(),
+        [0x0, 0x0, 0xF, 0xE] => "LOW".to_string(),
+        [0x0, 0x0, 0xF, 0xF] => "HIGH".to_string(),
+        [0x1, ..] => format!("JP {nnn:03X}"),
+        [0x2, ..] => format!("CALL {nnn:03X}"),
+        [0x3, x, ..] => format!("SE V{x:X}, {nn:02X}"),
+        [0x4, x, ..] => format!("SNE V{x:X}, {nn:02X}"),
+        [0x5, x, y, 0x0] => format!("SE V{x:X}, V{y:X}"),
+        [0x6, x, ..] => format!("LD V{x:X}, {nn:02X}"),
+        [0x7, x, ..] => format!("ADD V{x:X}, {nn:02X}"),
+        [0x8, x, y, 0x0] => format!("LD V{x:X}, V{y:X}"),
+        [0x8, x, y, 0x1] => format!("OR V{x:X}, V{y:X}"),
+        [0x8, x, y, 0x2] => format!("AND V{x:X}, V{y:X}"),
+        [0x8, x, y, 0x3] => format!("XOR V{x:X}, V{y:X}"),
+        [0x8, x, y, 0x4] => format!("ADD V{x:X}, V{y:X}"),
+        [0x8, x, y, 0x5] => format!("SUB V{x:X}, V{y:X}"),
+        [0x8, x, _, 0x6] => format!("SHR V{x:X}"),
+        [0x8, x, y, 0x7] => format!("SUBN V{x:X}, V{y:X}"),
+        [0x8, x, _, 0xE] => format!("SHL V{x:X}"),
+        [0x9, x, y, 0x0] => format!("SNE V{x:X}, V{y:X}"),
+        [0xA, ..] => format!("LD I, {nnn:03X}"),
+        [0xB, x, ..] => format!("JP V0/V{x:X}, {nnn:03X}"),
+        [0xC, x, ..] => format!("RND V{x:X}, {nn:02X}"),
+        [0xD, x, y, n] => format!("DRW V{x:X}, V{y:X}, {n:X}"),
+        [0xE, x, 0x9, 0xE] => format!("SKP V{x:X}"),
+        [0xE, x, 0xA, 0x1] => format!("SKNP V{x:X}"),
+        [0xF, x, 0x0, 0x7] => format!("LD V{x:X}, DT"),
+        [0xF, x, 0x0, 0xA] => format!("LD V{x:X}, K"),
+        [0xF, x, 0x1, 0x5] => format!("LD DT, V{x:X}"),
+        [0xF, x, 0x1, 0x8] => format!("LD ST, V{x:X}"),
+        [0xF, x, 0x1, 0xE] => format!("ADD I, V{x:X}"),
+        [0xF, x, 0x2, 0x9] => format!("LD F, V{x:X}"),
+        [0xF, x, 0x3, 0x0] => format!("LD HF, V{x:X}"),
+        [0xF, x, 0x3, 0x3] => format!("LD B, V{x:X}"),
+        [0xF, x, 0x5, 0x5] => format!("LD [I], V{x:X}"),
+        [0xF, x, 0x6, 0x5] => format!("LD V{x:X}, [I]"),
+        [0xF, x, 0x7, 0x5] => format!("LD R, V{x:X}"),
+        [0xF, x, 0x8, 0x5] => format!("LD V{x:X}, R"),
+        _ => format!("??? ({instruction:04X})"),
+    }
+}
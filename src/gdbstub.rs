@@ -0,0 +1,273 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use anyhow::Context;
+use chip8::State;
+
+/// Number of bytes in the fixed register layout sent by the `g`/`G`
+/// packets: 16 one-byte `Vx` registers, a two-byte `index_register`, a
+/// two-byte `program_counter`, and one-byte delay/sound timers.
+const REGISTER_BYTES: usize = 16 + 2 + 2 + 1 + 1;
+
+/// What the GDB client asked the emulator to do after a round of packet
+/// handling.
+pub(crate) enum GdbAction {
+    Continue,
+    Step,
+}
+
+/// A minimal GDB Remote Serial Protocol server exposing `State` over a TCP
+/// socket, so any standard GDB front-end can drive CHIP-8 debugging.
+pub(crate) struct GdbStub {
+    stream: TcpStream,
+    breakpoints: Vec<u16>,
+    single_step_pending: bool,
+    halted: bool,
+}
+
+impl GdbStub {
+    /// Binds `port` and blocks until a GDB client connects.
+    pub(crate) fn listen(port: u16) -> anyhow::Result<Self> {
+        let listener =
+            TcpListener::bind(("127.0.0.1", port)).context("Couldn't bind GDB stub socket")?;
+        println!("Waiting for a GDB connection on 127.0.0.1:{port}...");
+
+        let (stream, addr) = listener.accept().context("Couldn't accept GDB connection")?;
+        println!("GDB connected from {addr}");
+
+        Ok(Self {
+            stream,
+            breakpoints: Vec::new(),
+            single_step_pending: false,
+            // Start halted: a GDB client expects the target to be stopped on
+            // attach, so it gets a chance to set breakpoints with `Z0,...`
+            // before anything runs. The first `c`/`s` clears this.
+            halted: true,
+        })
+    }
+
+    /// Called once per instruction, before it is fetched, mirroring
+    /// `Debugger::should_stop`.
+    pub(crate) fn should_stop(&mut self, program_counter: u16) -> bool {
+        if self.halted {
+            return true;
+        }
+
+        if std::mem::take(&mut self.single_step_pending) {
+            return true;
+        }
+
+        self.breakpoints.contains(&program_counter)
+    }
+
+    /// Sends an unsolicited stop reply, e.g. after hitting a breakpoint.
+    pub(crate) fn report_stop(&mut self) -> anyhow::Result<()> {
+        self.send_packet("S05")
+    }
+
+    /// Services RSP packets until the client asks to continue or single-step.
+    pub(crate) fn serve(&mut self, state: &mut State) -> anyhow::Result<GdbAction> {
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                continue;
+            };
+
+            let mut chars = packet.chars();
+            match chars.next() {
+                Some('?') => self.send_packet("S05")?,
+                Some('g') => self.send_packet(&encode_registers(state))?,
+                Some('G') => {
+                    decode_registers(chars.as_str(), state)?;
+                    self.send_packet("OK")?;
+                }
+                Some('m') => self.handle_read_memory(chars.as_str(), state)?,
+                Some('M') => self.handle_write_memory(chars.as_str(), state)?,
+                Some('c') => {
+                    self.halted = false;
+                    return Ok(GdbAction::Continue);
+                }
+                Some('s') => {
+                    self.halted = false;
+                    self.single_step_pending = true;
+                    return Ok(GdbAction::Step);
+                }
+                Some('Z') => {
+                    if let Some(addr) = parse_breakpoint_addr(chars.as_str()) {
+                        self.breakpoints.push(addr);
+                        self.send_packet("OK")?;
+                    } else {
+                        self.send_packet("E01")?;
+                    }
+                }
+                Some('z') => {
+                    if let Some(addr) = parse_breakpoint_addr(chars.as_str()) {
+                        self.breakpoints.retain(|&bp| bp != addr);
+                        self.send_packet("OK")?;
+                    } else {
+                        self.send_packet("E01")?;
+                    }
+                }
+                // Unsupported packet: GDB expects an empty reply.
+                _ => self.send_packet("")?,
+            }
+        }
+    }
+
+    fn handle_read_memory(&mut self, rest: &str, state: &State) -> anyhow::Result<()> {
+        let Some((addr, len)) = parse_addr_len(rest) else {
+            return self.send_packet("E01");
+        };
+
+        let Some(end) = addr.checked_add(len) else {
+            return self.send_packet("E01");
+        };
+
+        match state.memory.get(addr..end) {
+            Some(bytes) => self.send_packet(&hex_encode(bytes)),
+            None => self.send_packet("E01"),
+        }
+    }
+
+    fn handle_write_memory(&mut self, rest: &str, state: &mut State) -> anyhow::Result<()> {
+        let Some((header, data)) = rest.split_once(':') else {
+            return self.send_packet("E01");
+        };
+        let Some((addr, len)) = parse_addr_len(header) else {
+            return self.send_packet("E01");
+        };
+        let Some(bytes) = hex_decode(data) else {
+            return self.send_packet("E01");
+        };
+
+        if bytes.len() != len {
+            return self.send_packet("E01");
+        }
+
+        let Some(end) = addr.checked_add(len) else {
+            return self.send_packet("E01");
+        };
+
+        match state.memory.get_mut(addr..end) {
+            Some(dst) => {
+                dst.copy_from_slice(&bytes);
+                self.send_packet("OK")
+            }
+            None => self.send_packet("E01"),
+        }
+    }
+
+    /// Reads one `$<payload>#<checksum>` packet, ACKing it with `+`. Bytes
+    /// outside of a packet (stray `+`/`-` acks, a `\x03` interrupt) are
+    /// discarded. A checksum mismatch is NACK'd with `-` and retried by the
+    /// client, so this only returns once a packet has been ACK'd; it
+    /// returns `None` if that ACK'd payload isn't valid UTF-8.
+    fn read_packet(&mut self) -> anyhow::Result<Option<String>> {
+        loop {
+            let byte = self.read_byte()?;
+            if byte != b'$' {
+                continue;
+            }
+
+            let mut payload = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => payload.push(byte),
+                }
+            }
+
+            let checksum_hex = [self.read_byte()?, self.read_byte()?];
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum_hex)?, 16).ok();
+            let actual = payload.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+            if expected == Some(actual) {
+                self.stream.write_all(b"+").context("Couldn't ACK packet")?;
+                return Ok(String::from_utf8(payload).ok());
+            } else {
+                self.stream
+                    .write_all(b"-")
+                    .context("Couldn't NACK packet")?;
+            }
+        }
+    }
+
+    fn send_packet(&mut self, payload: &str) -> anyhow::Result<()> {
+        let checksum = payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let framed = format!("${payload}#{checksum:02x}");
+        self.stream
+            .write_all(framed.as_bytes())
+            .context("Couldn't send GDB packet")?;
+
+        // Wait for the client's ACK before moving on.
+        let _ = self.read_byte()?;
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> anyhow::Result<u8> {
+        let mut byte = [0u8; 1];
+        self.stream
+            .read_exact(&mut byte)
+            .context("GDB connection closed")?;
+        Ok(byte[0])
+    }
+}
+
+fn encode_registers(state: &State) -> String {
+    let mut bytes = Vec::with_capacity(REGISTER_BYTES);
+    bytes.extend_from_slice(&state.variable_registers);
+    bytes.extend_from_slice(&state.index_register.to_le_bytes());
+    bytes.extend_from_slice(&state.program_counter.to_le_bytes());
+    bytes.push(state.delay_timer);
+    bytes.push(state.sound_timer);
+    hex_encode(&bytes)
+}
+
+fn decode_registers(hex: &str, state: &mut State) -> anyhow::Result<()> {
+    let bytes = hex_decode(hex).context("Malformed register data in G packet")?;
+    if bytes.len() != REGISTER_BYTES {
+        anyhow::bail!(
+            "Expected {REGISTER_BYTES} register bytes, got {}",
+            bytes.len()
+        );
+    }
+
+    state.variable_registers.copy_from_slice(&bytes[0..16]);
+    state.index_register = u16::from_le_bytes([bytes[16], bytes[17]]);
+    state.program_counter = u16::from_le_bytes([bytes[18], bytes[19]]);
+    state.delay_timer = bytes[20];
+    state.sound_timer = bytes[21];
+    Ok(())
+}
+
+/// Parses the `addr,len` argument shared by `m` and the header of `M`.
+fn parse_addr_len(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((
+        usize::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Parses the `addr,kind` portion of a `Z0,addr,kind`/`z0,addr,kind` packet.
+/// We only support software breakpoints (type 0); the `kind` field is
+/// unused since every CHIP-8 instruction is two bytes.
+fn parse_breakpoint_addr(s: &str) -> Option<u16> {
+    let rest = s.strip_prefix("0,")?;
+    let (addr, _kind) = rest.split_once(',')?;
+    u16::from_str_radix(addr, 16).ok()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,809 @@
+mod arraystack;
+mod disasm;
+
+use arraystack::Stack;
+pub use disasm::disassemble;
+
+use anyhow::{bail, Context};
+use rand::{Rng, RngCore};
+
+pub const MEM_SIZE: usize = 4096;
+pub const STACK_SIZE: usize = 16;
+
+/// How many `(program_counter, instruction)` pairs `State` remembers for
+/// `crash_report`.
+const PC_HISTORY_LEN: usize = 16;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+pub const PROGRAM_START: usize = 0x200;
+
+const FONT_START: usize = 0x050;
+const FONT_END: usize = 0x0A0;
+const FONT_CHAR_SIZE_BYTES: usize = 5;
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// SUPER-CHIP large digits, 8x10 pixels each, one byte per row. Stored right
+// after the standard font so FX30 can index into it the same way FX29 does.
+const HIRES_FONT_START: usize = FONT_END;
+const HIRES_FONT_END: usize = HIRES_FONT_START + 100;
+const HIRES_FONT_CHAR_SIZE_BYTES: usize = 10;
+const HIRES_FONT: [u8; 100] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+];
+
+/// Fixed-size ring buffer of the most recently executed `(program_counter,
+/// instruction)` pairs, oldest overwritten first. Backs `State::crash_report`
+/// so a failing `step` can show how execution got there.
+#[derive(Debug, Clone, Copy)]
+struct PcHistory {
+    entries: [(u16, u16); PC_HISTORY_LEN],
+    len: usize,
+    next: usize,
+}
+
+impl PcHistory {
+    fn new() -> Self {
+        Self {
+            entries: [(0, 0); PC_HISTORY_LEN],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, program_counter: u16, instruction: u16) {
+        self.entries[self.next] = (program_counter, instruction);
+        self.next = (self.next + 1) % PC_HISTORY_LEN;
+        self.len = (self.len + 1).min(PC_HISTORY_LEN);
+    }
+
+    /// Oldest-to-newest order.
+    fn iter(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let start = if self.len < PC_HISTORY_LEN {
+            0
+        } else {
+            self.next
+        };
+        (0..self.len).map(move |i| self.entries[(start + i) % PC_HISTORY_LEN])
+    }
+}
+
+/// Behavioural quirks that differ between CHIP-8 implementations. `main`
+/// turns CLI flags into one of these; `State::step` never looks at CLI
+/// concerns directly, which is what keeps it unit-testable.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Quirks {
+    pub bitshift_ignores_vy: bool,
+    pub jump_with_offset_uses_vx: bool,
+    pub add_to_index_ignores_overflow: bool,
+    pub store_and_load_increment_index: bool,
+}
+
+/// Injectable input source for the `EX9E`/`EXA1`/`FX0A` opcodes. The minifb
+/// `Window` used by `main` implements this via a small wrapper; tests can
+/// supply a scripted implementation instead.
+pub trait Keypad {
+    fn is_down(&self, key: u8) -> bool;
+    fn first_pressed(&self) -> Option<u8>;
+}
+
+/// Injectable audio output driven by `sound_timer`. `main` turns a concrete
+/// backend on while the timer is nonzero and off once it reaches zero; a
+/// `--mute` run or a headless test can supply a no-op implementation instead.
+pub trait Buzzer {
+    fn set_playing(&mut self, on: bool);
+}
+
+/// What changed as a result of a single `State::step` call.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StepOutcome {
+    pub display_changed: bool,
+    pub should_beep: bool,
+    /// Set by the SUPER-CHIP `00FD` opcode; `main` treats this like the user
+    /// closing the window.
+    pub should_exit: bool,
+}
+
+pub struct State {
+    pub memory: [u8; MEM_SIZE],
+    pub program_counter: u16,
+    pub index_register: u16,
+    pub stack: Stack<u16, STACK_SIZE>,
+    pub variable_registers: [u8; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// Row-major, `width() * height()` pixels. Sized to `HIRES_WIDTH *
+    /// HIRES_HEIGHT` up front when SUPER-CHIP mode is enabled so toggling
+    /// into hires mid-ROM never needs to reallocate.
+    pub display: Vec<bool>,
+    /// SUPER-CHIP RPL user-flags, backing `FX75`/`FX85`.
+    rpl_flags: [u8; 8],
+    quirks: Quirks,
+    schip: bool,
+    hires: bool,
+    pc_history: PcHistory,
+}
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("program_counter", &self.program_counter)
+            .field("index_register", &self.index_register)
+            .field("delay_timer", &self.delay_timer)
+            .field("sound_timer", &self.sound_timer)
+            .field("stack", &self.stack)
+            .field("variable_registers", &self.variable_registers)
+            .finish()
+    }
+}
+
+impl State {
+    /// `start_address` is both where `load_program` writes the ROM and the
+    /// initial `program_counter`, letting ROMs assembled for non-standard
+    /// origins run unmodified. Pass `PROGRAM_START` for ordinary CHIP-8 ROMs.
+    pub fn new(quirks: Quirks, schip: bool, start_address: u16) -> Self {
+        let mut memory = [0; MEM_SIZE];
+        memory[FONT_START..FONT_END].copy_from_slice(&FONT);
+        memory[HIRES_FONT_START..HIRES_FONT_END].copy_from_slice(&HIRES_FONT);
+
+        let display_capacity = if schip {
+            HIRES_WIDTH * HIRES_HEIGHT
+        } else {
+            LORES_WIDTH * LORES_HEIGHT
+        };
+
+        Self {
+            memory,
+            program_counter: start_address,
+            index_register: 0,
+            stack: Stack::new(),
+            variable_registers: [0; 16],
+            delay_timer: 0,
+            sound_timer: 0,
+            display: vec![false; display_capacity],
+            rpl_flags: [0; 8],
+            quirks,
+            schip,
+            hires: false,
+            pc_history: PcHistory::new(),
+        }
+    }
+
+    /// Disassembles the recently executed instructions (oldest first) and
+    /// dumps registers, stack, and index. `main` prints this when `step`
+    /// returns an error, so a crashing ROM leaves a trail of how it got there.
+    pub fn crash_report(&self) -> String {
+        let mut report = String::from("Recent instructions:\n");
+        for (program_counter, instruction) in self.pc_history.iter() {
+            report.push_str(&format!(
+                "  {:04X}: {:04X}  {}\n",
+                program_counter,
+                instruction,
+                disassemble(instruction)
+            ));
+        }
+        report.push_str(&format!("\n{:?}\n", self));
+        report
+    }
+
+    /// Copies `program` into memory starting at `program_counter` (set from
+    /// `start_address` in `new`). Rejects ROMs that wouldn't fit rather than
+    /// panicking on an out-of-bounds slice.
+    pub fn load_program(&mut self, program: &[u8]) -> anyhow::Result<()> {
+        let start = self.program_counter as usize;
+        if start > MEM_SIZE {
+            bail!(
+                "Start address {:03X} is outside of the {} byte address space",
+                start,
+                MEM_SIZE
+            );
+        }
+
+        let available = MEM_SIZE - start;
+        if program.len() > available {
+            bail!(
+                "ROM too large: {} bytes, but only {} bytes available from address {:03X}",
+                program.len(),
+                available,
+                start
+            );
+        }
+
+        self.memory[start..][..program.len()].copy_from_slice(program);
+        Ok(())
+    }
+
+    /// Current display width: `LORES_WIDTH` unless SUPER-CHIP mode is active
+    /// and the ROM has switched into hires via `00FF`.
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    /// Current display height; see `width`.
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let width = self.width();
+        let height = self.height();
+
+        for y in (0..height).rev() {
+            for x in 0..width {
+                self.display[y * width + x] =
+                    y >= n && self.display[(y - n) * width + x];
+            }
+        }
+    }
+
+    fn scroll_right(&mut self) {
+        let width = self.width();
+        let height = self.height();
+
+        for y in 0..height {
+            for x in (0..width).rev() {
+                self.display[y * width + x] = x >= 4 && self.display[y * width + x - 4];
+            }
+        }
+    }
+
+    fn scroll_left(&mut self) {
+        let width = self.width();
+        let height = self.height();
+
+        for y in 0..height {
+            for x in 0..width {
+                self.display[y * width + x] =
+                    x + 4 < width && self.display[y * width + x + 4];
+            }
+        }
+    }
+
+    /// Fetches, decodes, and executes a single instruction at
+    /// `program_counter`, mutating `self` and reporting whether the display
+    /// changed or a beep should start.
+    pub fn step(
+        &mut self,
+        keys: &impl Keypad,
+        rng: &mut impl RngCore,
+    ) -> anyhow::Result<StepOutcome> {
+        let mut outcome = StepOutcome::default();
+
+        let [instruction_hi, instruction_lo]: [u8; 2] = self.memory
+            [self.program_counter as usize..][..2]
+            .try_into()
+            .context("Out-of-bounds while trying to fetch instruction")?;
+        self.program_counter += 2;
+
+        let instruction = u16::from_be_bytes([instruction_hi, instruction_lo]);
+        self.pc_history
+            .push(self.program_counter - 2, instruction);
+
+        let [[nibble1, nibble2], [nibble3, nibble4]] =
+            instruction.to_be_bytes().map(|b| [b >> 4, b & 0x0F]);
+        let nibbles = [nibble1, nibble2, nibble3, nibble4];
+        let nnn = instruction & 0x0FFF;
+        let nn = instruction_lo;
+
+        let width = self.width();
+        let height = self.height();
+
+        match nibbles {
+            [0x0, 0x0, 0xE, 0x0] => {
+                self.display.fill(false);
+                outcome.display_changed = true;
+            }
+            [0x0, 0x0, 0xE, 0xE] => {
+                self.program_counter = self.stack.pop().context("Tried to pop from empty stack")?
+            }
+            [0x0, 0x0, 0xC, n] if self.schip => {
+                self.scroll_down(n as usize);
+                outcome.display_changed = true;
+            }
+            [0x0, 0x0, 0xF, 0xB] if self.schip => {
+                self.scroll_right();
+                outcome.display_changed = true;
+            }
+            [0x0, 0x0, 0xF, 0xC] if self.schip => {
+                self.scroll_left();
+                outcome.display_changed = true;
+            }
+            [0x0, 0x0, 0xF, 0xE] if self.schip => {
+                self.hires = false;
+                outcome.display_changed = true;
+            }
+            [0x0, 0x0, 0xF, 0xF] if self.schip => {
+                self.hires = true;
+                outcome.display_changed = true;
+            }
+            [0x0, 0x0, 0xF, 0xD] if self.schip => outcome.should_exit = true,
+            [0x1, ..] => self.program_counter = nnn,
+            [0x2, ..] => {
+                self.stack
+                    .try_push(self.program_counter)
+                    .context("Overflowed stack")?;
+                self.program_counter = nnn;
+            }
+            [0x3, x, ..] => {
+                if self.variable_registers[x as usize] == nn {
+                    self.program_counter += 2;
+                }
+            }
+            [0x4, x, ..] => {
+                if self.variable_registers[x as usize] != nn {
+                    self.program_counter += 2;
+                }
+            }
+            [0x5, x, y, 0x0] => {
+                if self.variable_registers[x as usize] == self.variable_registers[y as usize] {
+                    self.program_counter += 2;
+                }
+            }
+            [0x6, x, ..] => self.variable_registers[x as usize] = nn,
+            [0x7, x, ..] => {
+                let vx = &mut self.variable_registers[x as usize];
+                *vx = vx.wrapping_add(nn);
+            }
+            [0x8, x, y, instr] => {
+                // We assign VF last if VX and VF overlap.
+                // Due to borrow checker, we'll reference VF through self.variable_registers[0xF]
+                // after we're done operating on VX + VY
+                let vy = self.variable_registers[y as usize];
+                let vx = &mut self.variable_registers[x as usize];
+
+                match instr {
+                    0x0 => *vx = vy,
+                    0x1 => *vx |= vy,
+                    0x2 => *vx &= vy,
+                    0x3 => *vx ^= vy,
+                    0x4 => {
+                        let (sum, overflowed) = vx.overflowing_add(vy);
+                        *vx = sum;
+                        self.variable_registers[0xF] = if overflowed { 1 } else { 0 };
+                    }
+                    0x5 => {
+                        let (difference, overflowed) = vx.overflowing_sub(vy);
+                        *vx = difference;
+                        // Note that CHIP-8 sets VF to 1 only if we didn't underflow
+                        self.variable_registers[0xF] = if overflowed { 0 } else { 1 };
+                    }
+                    0x6 => {
+                        if !self.quirks.bitshift_ignores_vy {
+                            *vx = vy;
+                        }
+                        let (result, overflowed) = vx.overflowing_shr(1);
+                        *vx = result;
+                        self.variable_registers[0xF] = if overflowed { 1 } else { 0 };
+                    }
+                    0x7 => {
+                        let (difference, overflowed) = vy.overflowing_sub(*vx);
+                        *vx = difference;
+                        // Note that CHIP-8 sets VF to 1 only if we didn't underflow
+                        self.variable_registers[0xF] = if overflowed { 0 } else { 1 };
+                    }
+                    0xE => {
+                        if !self.quirks.bitshift_ignores_vy {
+                            *vx = vy;
+                        }
+                        let (result, overflowed) = vx.overflowing_shl(1);
+                        *vx = result;
+                        self.variable_registers[0xF] = if overflowed { 1 } else { 0 };
+                    }
+                    _ => bail!("Unexpected arithmetic instruction: {:04x}", instruction),
+                }
+            }
+            [0x9, x, y, 0x0] => {
+                if self.variable_registers[x as usize] != self.variable_registers[y as usize] {
+                    self.program_counter += 2;
+                }
+            }
+            [0xA, ..] => self.index_register = nnn,
+            [0xB, x, ..] => {
+                if self.quirks.jump_with_offset_uses_vx {
+                    self.program_counter = nnn + self.variable_registers[x as usize] as u16;
+                } else {
+                    self.program_counter = nnn + self.variable_registers[0] as u16;
+                }
+            }
+            [0xC, x, ..] => {
+                let random_num: u8 = rng.gen();
+                self.variable_registers[x as usize] = random_num & nn;
+            }
+            [0xD, x, y, 0x0] if self.hires => {
+                // SUPER-CHIP 16x16 sprite: 32 bytes, two per row.
+                let x_start = (self.variable_registers[x as usize] as usize) % width;
+                let y_start = (self.variable_registers[y as usize] as usize) % height;
+
+                self.variable_registers[0xF] = 0;
+
+                let sprite = self
+                    .memory
+                    .get((self.index_register as usize)..)
+                    .and_then(|range| range.get(..32))
+                    .context("Out-of-bounds while trying to read 16x16 sprite")?;
+
+                for (row_index, row_bytes) in sprite.chunks_exact(2).enumerate() {
+                    let y = y_start + row_index;
+                    if y >= height {
+                        break;
+                    }
+
+                    let row = u16::from_be_bytes([row_bytes[0], row_bytes[1]]);
+                    for i in 0..16 {
+                        let x = x_start + i;
+                        if x >= width {
+                            break;
+                        }
+
+                        if (row >> (15 - i)) & 0x01 == 1 {
+                            let pixel = &mut self.display[y * width + x];
+                            *pixel = !*pixel;
+
+                            if !(*pixel) {
+                                self.variable_registers[0xF] = 1;
+                            }
+                        }
+                    }
+                }
+
+                outcome.display_changed = true;
+            }
+            [0xD, x, y, n] => {
+                let x_start = (self.variable_registers[x as usize] as usize) % width;
+                let y_start = (self.variable_registers[y as usize] as usize) % height;
+
+                self.variable_registers[0xF] = 0;
+
+                let sprite = self
+                    .memory
+                    .get((self.index_register as usize)..)
+                    .and_then(|range| range.get(..(n as usize)))
+                    .context("Out-of-bounds while trying to read sprite")?;
+
+                // TODO Lots of arithmetic here. Deal with overflow
+                for (sprite_row, y) in sprite.iter().zip(y_start..) {
+                    if y >= height {
+                        break;
+                    }
+
+                    for (i, x) in (x_start..x_start.saturating_add(8)).enumerate() {
+                        if x >= width {
+                            break;
+                        }
+
+                        if (sprite_row >> (8 - i - 1)) & 0x01 == 1 {
+                            let pixel = &mut self.display[y * width + x];
+                            *pixel = !*pixel;
+
+                            if !(*pixel) {
+                                self.variable_registers[0xF] = 1;
+                            }
+                        }
+                    }
+                }
+
+                outcome.display_changed = true;
+            }
+            [0xE, x, 0x9, 0xE] => {
+                if keys.is_down(self.variable_registers[x as usize]) {
+                    self.program_counter += 2;
+                }
+            }
+            [0xE, x, 0xA, 0x1] => {
+                if !keys.is_down(self.variable_registers[x as usize]) {
+                    self.program_counter += 2;
+                }
+            }
+            [0xF, x, 0x0, 0x7] => self.variable_registers[x as usize] = self.delay_timer,
+            [0xF, x, 0x0, 0xA] => match keys.first_pressed() {
+                Some(key) => self.variable_registers[x as usize] = key,
+                None => self.program_counter -= 2,
+            },
+            [0xF, x, 0x1, 0x5] => self.delay_timer = self.variable_registers[x as usize],
+            [0xF, x, 0x1, 0x8] => {
+                self.sound_timer = self.variable_registers[x as usize];
+                outcome.should_beep = self.sound_timer > 0;
+            }
+            [0xF, x, 0x1, 0xE] => {
+                self.index_register += self.variable_registers[x as usize] as u16;
+                if !self.quirks.add_to_index_ignores_overflow {
+                    if self.index_register > 0xFFF {
+                        self.variable_registers[0xF] = 1; // TODO Set to 0 otherwise?
+                    }
+                }
+            }
+            [0xF, x, 0x2, 0x9] => {
+                let vx = self.variable_registers[x as usize];
+                self.index_register = (FONT_START + FONT_CHAR_SIZE_BYTES * vx as usize) as u16;
+            }
+            [0xF, x, 0x3, 0x0] if self.schip => {
+                let vx = self.variable_registers[x as usize];
+                self.index_register =
+                    (HIRES_FONT_START + HIRES_FONT_CHAR_SIZE_BYTES * vx as usize) as u16;
+            }
+            [0xF, x, 0x3, 0x3] => {
+                let vx = self.variable_registers[x as usize];
+                let [hundreds, tens, ones]: [u16; 3] =
+                    [vx / 100, (vx / 10) % 10, vx % 10].map(u16::from);
+                self.index_register = (hundreds << 8) | (tens << 4) | ones;
+            }
+            [0xF, x, 0x5, 0x5] => {
+                let dst = self
+                    .memory
+                    .get_mut((self.index_register as usize)..)
+                    .and_then(|range| range.get_mut(..=(x as usize)))
+                    .context("Overflowed while trying to store registers V0 through VX")?;
+                let src = &self.variable_registers[..=(x as usize)];
+                dst.copy_from_slice(src);
+
+                if self.quirks.store_and_load_increment_index {
+                    self.index_register += x as u16;
+                }
+            }
+            [0xF, x, 0x6, 0x5] => {
+                let dst = &mut self.variable_registers[..=(x as usize)];
+                let src = self
+                    .memory
+                    .get((self.index_register as usize)..)
+                    .and_then(|range| range.get(..=(x as usize)))
+                    .context("Overflowed while trying to store registers V0 through VX")?;
+                dst.copy_from_slice(src);
+
+                if self.quirks.store_and_load_increment_index {
+                    self.index_register += x as u16;
+                }
+            }
+            [0xF, x, 0x7, 0x5] if self.schip => {
+                let x = x as usize;
+                if x >= self.rpl_flags.len() {
+                    bail!("FX75 only supports V0-V7 (got V{:X})", x);
+                }
+                self.rpl_flags[..=x].copy_from_slice(&self.variable_registers[..=x]);
+            }
+            [0xF, x, 0x8, 0x5] if self.schip => {
+                let x = x as usize;
+                if x >= self.rpl_flags.len() {
+                    bail!("FX85 only supports V0-V7 (got V{:X})", x);
+                }
+                self.variable_registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+            }
+            _ => bail!("Unexpected instruction: {:04x}", instruction),
+        }
+
+        Ok(outcome)
+    }
+
+    /// Advances the delay and sound timers by one tick. `main` calls this at
+    /// `TIMER_UPDATE_RATE_HZ`, independently of how many instructions run per
+    /// second.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    /// A `Keypad` whose pressed key is set directly by the test.
+    #[derive(Default)]
+    struct ScriptedKeypad {
+        pressed: Option<u8>,
+    }
+
+    impl Keypad for ScriptedKeypad {
+        fn is_down(&self, key: u8) -> bool {
+            self.pressed == Some(key)
+        }
+
+        fn first_pressed(&self) -> Option<u8> {
+            self.pressed
+        }
+    }
+
+    fn state_at(program: &[u8]) -> State {
+        let mut state = State::new(Quirks::default(), false, PROGRAM_START as u16);
+        state.load_program(program).unwrap();
+        state
+    }
+
+    fn schip_state_at(program: &[u8]) -> State {
+        let mut state = State::new(Quirks::default(), true, PROGRAM_START as u16);
+        state.load_program(program).unwrap();
+        state
+    }
+
+    #[test]
+    fn load_program_rejects_roms_too_large_to_fit() {
+        let mut state = State::new(Quirks::default(), false, PROGRAM_START as u16);
+        let oversized = vec![0u8; MEM_SIZE - PROGRAM_START + 1];
+
+        assert!(state.load_program(&oversized).is_err());
+    }
+
+    #[test]
+    fn load_program_rejects_start_address_outside_memory() {
+        let mut state = State::new(Quirks::default(), false, u16::MAX);
+
+        assert!(state.load_program(&[]).is_err());
+    }
+
+    #[test]
+    fn sets_register_from_immediate() {
+        let mut state = state_at(&[0x61, 0x23]); // LD V1, 0x23
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        state.step(&keys, &mut rng).unwrap();
+
+        assert_eq!(state.variable_registers[1], 0x23);
+    }
+
+    #[test]
+    fn jumps_to_address() {
+        let mut state = state_at(&[0x12, 0x34]); // JP 0x234
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        state.step(&keys, &mut rng).unwrap();
+
+        assert_eq!(state.program_counter, 0x234);
+    }
+
+    #[test]
+    fn adds_immediate_with_wraparound() {
+        let mut state = state_at(&[0x70, 0x01]); // ADD V0, 1
+        state.variable_registers[0] = 0xFF;
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        state.step(&keys, &mut rng).unwrap();
+
+        assert_eq!(state.variable_registers[0], 0x00);
+    }
+
+    #[test]
+    fn skips_next_instruction_when_key_is_down() {
+        let mut state = state_at(&[0xE1, 0x9E]); // SKP V1
+        state.variable_registers[1] = 0x5;
+        let keys = ScriptedKeypad {
+            pressed: Some(0x5),
+        };
+        let mut rng = StepRng::new(0, 0);
+
+        state.step(&keys, &mut rng).unwrap();
+
+        assert_eq!(state.program_counter, (PROGRAM_START + 4) as u16);
+    }
+
+    #[test]
+    fn blocks_on_key_wait_until_a_key_is_pressed() {
+        let mut state = state_at(&[0xF1, 0x0A]); // LD V1, K
+        let mut rng = StepRng::new(0, 0);
+
+        state.step(&ScriptedKeypad::default(), &mut rng).unwrap();
+        assert_eq!(state.program_counter, PROGRAM_START as u16);
+
+        state
+            .step(&ScriptedKeypad { pressed: Some(0x7) }, &mut rng)
+            .unwrap();
+        assert_eq!(state.variable_registers[1], 0x7);
+        assert_eq!(state.program_counter, (PROGRAM_START + 2) as u16);
+    }
+
+    #[test]
+    fn unknown_instruction_is_an_error() {
+        let mut state = state_at(&[0x00, 0x00]); // unmapped 0NNN
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        assert!(state.step(&keys, &mut rng).is_err());
+    }
+
+    #[test]
+    fn draw_sprite_reading_past_memory_errors_instead_of_panicking() {
+        let mut state = state_at(&[0xD0, 0x02]); // DRW V0, V0, 2
+        state.index_register = (MEM_SIZE - 1) as u16;
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        assert!(state.step(&keys, &mut rng).is_err());
+        // The error is a `Result`, not a panic, so a crash report can still
+        // be produced from the state it left behind.
+        state.crash_report();
+    }
+
+    #[test]
+    fn schip_opcodes_are_unknown_outside_schip_mode() {
+        let mut state = state_at(&[0x00, 0xFF]); // 00FF, hires-only toggle
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        assert!(state.step(&keys, &mut rng).is_err());
+    }
+
+    #[test]
+    fn toggles_into_hires_mode() {
+        let mut state = schip_state_at(&[0x00, 0xFF]); // 00FF: enable hires
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        assert_eq!(state.width(), LORES_WIDTH);
+
+        state.step(&keys, &mut rng).unwrap();
+
+        assert_eq!(state.width(), HIRES_WIDTH);
+        assert_eq!(state.height(), HIRES_HEIGHT);
+    }
+
+    #[test]
+    fn scroll_right_shifts_pixels_by_four() {
+        let mut state = schip_state_at(&[0x00, 0xFB]); // 00FB: scroll right 4
+        state.display[0] = true;
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        state.step(&keys, &mut rng).unwrap();
+
+        assert!(!state.display[0]);
+        assert!(state.display[4]);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip_through_fx75_and_fx85() {
+        let mut state = schip_state_at(&[
+            0x67, 0x2A, // LD V7, 0x2A
+            0xF7, 0x75, // FX75: save V0-V7 to RPL flags
+            0x67, 0x00, // LD V7, 0x00
+            0xF7, 0x85, // FX85: restore V0-V7 from RPL flags
+        ]);
+        let keys = ScriptedKeypad::default();
+        let mut rng = StepRng::new(0, 0);
+
+        for _ in 0..4 {
+            state.step(&keys, &mut rng).unwrap();
+        }
+
+        assert_eq!(state.variable_registers[7], 0x2A);
+    }
+}